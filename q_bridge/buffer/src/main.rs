@@ -1,24 +1,73 @@
 use prost::Message;
-use redis::aio::MultiplexedConnection;
-use redis::{AsyncCommands, from_redis_value, Value, streams::StreamReadOptions};
+use redis::{from_redis_value, streams::StreamReadOptions, AsyncCommands, Value};
 use std::collections::HashMap;
 use tracing::{error, info, instrument};
 use tracing_subscriber::prelude::*;
 
 // Import the protobuf definitions from the common crate
-use common::gateway::InternalRequest;
+use common::gateway::{InternalRequest, SubmitRequestResult};
+use common::redis_backend::{reply_channel, RedisBackend, StreamStore, STREAM_NAME};
 
-const REDIS_URL: &str = "redis://127.0.0.1:6379/";
-const STREAM_NAME: &str = "q_bridge_stream";
 const GROUP_NAME: &str = "q_bridge_group";
 const CONSUMER_NAME: &str = "buffer-consumer-1";
 
-// Creates the consumer group. Idempotent.
-async fn create_consumer_group(client: &redis::Client) -> redis::RedisResult<()> {
-    let mut conn = client.get_multiplexed_async_connection().await?;
-    let result: Result<(), _> = conn
-        .xgroup_create_mkstream(STREAM_NAME, GROUP_NAME, "0-0")
-        .await;
+// Stream that poison messages are moved to once they've been redelivered
+// `MAX_DELIVERIES` times. Kept under the same `{q_bridge}` hashtag as the
+// main stream purely for locality; cluster mode doesn't require it to
+// share a slot with `STREAM_NAME`.
+const DLQ_STREAM_NAME: &str = "{q_bridge}:q_bridge_dlq";
+// Hash of message id -> delivery count, incremented every time the
+// reclaim loop claims that entry. `XCLAIM`/`XAUTOCLAIM` bump Redis's own
+// internal delivery counter too, but we keep our own so the threshold
+// check doesn't depend on parsing `XPENDING`'s extended form.
+const DELIVERY_COUNT_KEY: &str = "{q_bridge}:q_bridge_delivery_counts";
+
+fn reclaim_interval() -> std::time::Duration {
+    let secs = std::env::var("RECLAIM_INTERVAL_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(30);
+    std::time::Duration::from_secs(secs)
+}
+
+fn reclaim_min_idle_ms() -> i64 {
+    std::env::var("RECLAIM_MIN_IDLE_MS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(30_000)
+}
+
+fn max_deliveries() -> i64 {
+    std::env::var("MAX_DELIVERIES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(5)
+}
+
+// Creates the consumer group. Idempotent. `XGROUP CREATE` isn't part of
+// `StreamStore` since it's only ever called once at startup, so we match
+// on the backend directly to grab whatever connection type it offers.
+async fn create_consumer_group(redis: &RedisBackend) -> redis::RedisResult<()> {
+    let result: Result<(), _> = match redis {
+        RedisBackend::Single(client) => {
+            let mut conn = client.get_multiplexed_async_connection().await?;
+            conn.xgroup_create_mkstream(STREAM_NAME, GROUP_NAME, "0-0").await
+        }
+        RedisBackend::Pool { pool, .. } => {
+            let mut conn = pool.get().await.map_err(|e| {
+                redis::RedisError::from((
+                    redis::ErrorKind::IoError,
+                    "failed to acquire pooled Redis connection",
+                    e.to_string(),
+                ))
+            })?;
+            conn.xgroup_create_mkstream(STREAM_NAME, GROUP_NAME, "0-0").await
+        }
+        RedisBackend::Cluster { client, .. } => {
+            let mut conn = client.get_async_connection().await?;
+            conn.xgroup_create_mkstream(STREAM_NAME, GROUP_NAME, "0-0").await
+        }
+    };
     match result {
         Ok(_) => {
             info!(
@@ -40,31 +89,212 @@ async fn create_consumer_group(client: &redis::Client) -> redis::RedisResult<()>
     Ok(())
 }
 
-#[instrument(skip(conn))]
+#[instrument(skip(redis))]
 async fn process_message(
     message_id: &str,
     payload_bytes: &[u8],
-    conn: &mut MultiplexedConnection,
+    redis: &RedisBackend,
 ) -> redis::RedisResult<()> {
     match InternalRequest::decode(payload_bytes) {
         Ok(req) => {
+            // The gateway stashes the correlation id it minted (or received
+            // via `x-request-id`/`x-correlation-id`) under this metadata
+            // key so it survives the gateway -> buffer -> consumer hop even
+            // when it differs from `request_id` itself.
+            let correlation_id = req
+                .metadata
+                .get("x-request-id")
+                .cloned()
+                .unwrap_or_else(|| req.request_id.clone());
+            let span = tracing::info_span!("process_message", request_id = %correlation_id);
+            let _enter = span.enter();
+
             info!(
-                request_id = %req.request_id,
+                request_id = %correlation_id,
                 agent_id = %req.agent_id,
                 "Processing message"
             );
+            publish_result(&req.request_id, redis).await;
         }
         Err(e) => {
             error!(message_id = %message_id, "Failed to decode message: {}", e);
         }
     }
 
-    conn.xack::<_, _, _, ()>(STREAM_NAME, GROUP_NAME, &[message_id]).await?;
+    redis.xack(STREAM_NAME, GROUP_NAME, &[message_id]).await?;
     info!(message_id = %message_id, "Acknowledged message");
 
     Ok(())
 }
 
+// Publishes a completion result for a synchronous `SubmitRequestAndWait`
+// / `wait=true` caller that may be subscribed to this request's reply
+// channel. Nobody is necessarily listening (fire-and-forget callers
+// aren't), so a publish failure is logged and otherwise ignored.
+async fn publish_result(request_id: &str, redis: &RedisBackend) {
+    let result = SubmitRequestResult {
+        request_id: request_id.to_string(),
+        status: "completed".to_string(),
+        result: Vec::new(),
+    };
+
+    let mut buf = Vec::new();
+    if let Err(e) = result.encode(&mut buf) {
+        error!(request_id = %request_id, "Failed to encode result: {}", e);
+        return;
+    }
+
+    if let Err(e) = redis.publish(&reply_channel(request_id), &buf).await {
+        error!(request_id = %request_id, "Failed to publish result: {}", e);
+    }
+}
+
+// Moves a poison message to the DLQ: re-publishes the original payload
+// plus failure metadata, then acks it off the main group's PEL.
+#[instrument(skip(redis, payload))]
+async fn dead_letter(
+    redis: &RedisBackend,
+    message_id: &str,
+    payload: &[u8],
+    delivery_count: i64,
+) -> redis::RedisResult<()> {
+    error!(
+        message_id = %message_id,
+        delivery_count,
+        "Exceeded max deliveries, moving message to DLQ"
+    );
+
+    redis
+        .xadd(
+            DLQ_STREAM_NAME,
+            "*",
+            &[
+                ("payload", payload),
+                ("original_id", message_id.as_bytes()),
+                ("delivery_count", delivery_count.to_string().as_bytes()),
+                ("source_stream", STREAM_NAME.as_bytes()),
+            ],
+        )
+        .await?;
+
+    redis.xack(STREAM_NAME, GROUP_NAME, &[message_id]).await?;
+    info!(message_id = %message_id, "Dead-lettered and acknowledged message");
+
+    Ok(())
+}
+
+// Runs forever on a timer, taking ownership of PEL entries that have been
+// idle longer than `reclaim_min_idle_ms()` (i.e. whatever claimed them
+// died before acking) and either reprocessing them or, once they've been
+// redelivered too many times, routing them to the DLQ.
+async fn run_reclaim_loop(redis: RedisBackend) {
+    let mut ticker = tokio::time::interval(reclaim_interval());
+    loop {
+        ticker.tick().await;
+        if let Err(e) = reclaim_stalled_once(&redis).await {
+            error!("Stalled-message reclaim pass failed: {}", e);
+        }
+    }
+}
+
+/// Supervises `run_reclaim_loop` in its own task: if that task ever
+/// panics (a bug in it, or an unexpected Redis reply shape falling
+/// through an unhandled case), this is the only thing standing between
+/// that and the stalled-message reclaim + dead-letter safety net
+/// silently stopping forever with no crash and no log distinguishable
+/// from normal operation. Logs the failure and respawns the loop rather
+/// than letting it stay dead.
+async fn run_reclaim_loop_supervised(redis: RedisBackend) {
+    loop {
+        let handle = tokio::spawn(run_reclaim_loop(redis.clone()));
+        match handle.await {
+            Ok(()) => {
+                // `run_reclaim_loop` only returns by panicking; a clean
+                // return here would mean someone changed it to exit
+                // deliberately, so don't spin on that silently either.
+                error!("Reclaim loop exited unexpectedly; restarting it");
+            }
+            Err(e) => {
+                error!("Reclaim loop task panicked, restarting it: {}", e);
+            }
+        }
+    }
+}
+
+async fn reclaim_stalled_once(redis: &RedisBackend) -> redis::RedisResult<()> {
+    let min_idle_ms = reclaim_min_idle_ms();
+    let max_deliveries = max_deliveries();
+    let mut cursor = "0-0".to_string();
+
+    loop {
+        let reply = redis
+            .xautoclaim(STREAM_NAME, GROUP_NAME, CONSUMER_NAME, min_idle_ms, &cursor)
+            .await?;
+
+        if reply.claimed.is_empty() {
+            break;
+        }
+
+        for claimed in &reply.claimed {
+            let id = claimed.id.clone();
+            let fields: HashMap<String, Vec<u8>> = claimed
+                .map
+                .iter()
+                .filter_map(|(k, v)| {
+                    redis::from_redis_value::<Vec<u8>>(v).ok().map(|v| (k.clone(), v))
+                })
+                .collect();
+
+            let delivery_count = redis.hincr(DELIVERY_COUNT_KEY, &id, 1).await?;
+            info!(message_id = %id, delivery_count, "Reclaimed stalled message");
+
+            let Some(payload) = fields.get("payload") else {
+                error!(message_id = %id, "Reclaimed message had no payload field, acking it off");
+                redis.xack(STREAM_NAME, GROUP_NAME, &[id.as_str()]).await?;
+                continue;
+            };
+
+            if should_dead_letter(delivery_count, max_deliveries) {
+                dead_letter(redis, &id, payload, delivery_count).await?;
+            } else if let Err(e) = process_message(&id, payload, redis).await {
+                error!("Failed to reprocess reclaimed message {}: {}", id, e);
+            }
+        }
+
+        cursor = reply.cursor;
+        if cursor == "0-0" {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether a reclaimed message has been redelivered enough times to give
+/// up on it and route it to the DLQ instead of reprocessing it again.
+/// Pulled out of `reclaim_stalled_once` so the threshold itself can be
+/// unit-tested without a live Redis connection.
+fn should_dead_letter(delivery_count: i64, max_deliveries: i64) -> bool {
+    delivery_count > max_deliveries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_dead_letter_below_threshold() {
+        assert!(!should_dead_letter(1, 5));
+        assert!(!should_dead_letter(5, 5));
+    }
+
+    #[test]
+    fn should_dead_letter_above_threshold() {
+        assert!(should_dead_letter(6, 5));
+        assert!(should_dead_letter(100, 5));
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing_subscriber::registry()
@@ -73,19 +303,23 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .init();
 
     info!("Starting buffer consumer...");
-    let client = redis::Client::open(REDIS_URL)?;
+    // `RedisBackend::from_env` picks standalone / pool / cluster based on
+    // `REDIS_BACKEND` and `REDIS_URLS`, mirroring the gateway's setup.
+    let redis = RedisBackend::from_env().await?;
 
     // Create the group using a dedicated connection
-    create_consumer_group(&client).await?;
+    create_consumer_group(&redis).await?;
 
-    // Use a multiplexed connection for the main loop, as it's cloneable
-    let mut conn = client.get_multiplexed_async_connection().await?;
+    // Stalled-message reclaim runs independently of the main read loop,
+    // supervised so a panic in it gets logged and restarted rather than
+    // silently ending the reclaim + dead-letter safety net.
+    tokio::spawn(run_reclaim_loop_supervised(redis.clone()));
 
     info!("Waiting for messages...");
     loop {
         let opts = StreamReadOptions::default().count(1).group(GROUP_NAME, CONSUMER_NAME);
 
-        let response: Result<Value, _> = conn.xread_options(&[STREAM_NAME], &[">"], &opts).await;
+        let response: Result<Value, _> = redis.xread_options(STREAM_NAME, ">", &opts).await;
 
         let response = match response {
             Ok(val) => val,
@@ -111,8 +345,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                             let fields: HashMap<String, Vec<u8>> =
                                 from_redis_value(&message_parts[1])?;
                             if let Some(payload) = fields.get("payload") {
-                                // Clone the connection for the processing task
-                                if let Err(e) = process_message(&id, payload, &mut conn.clone()).await {
+                                if let Err(e) = process_message(&id, payload, &redis).await {
                                     error!("Failed to process message {}: {}", id, e);
                                 }
                             }
@@ -5,3 +5,7 @@
 pub mod gateway {
     tonic::include_proto!("gateway");
 }
+
+// Shared Redis connection backend (standalone / pooled / cluster) used by
+// the gateway and buffer consumer.
+pub mod redis_backend;
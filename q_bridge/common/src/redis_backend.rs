@@ -0,0 +1,442 @@
+// Shared Redis connectivity for the gateway and buffer consumer.
+//
+// Both binaries need to XADD/XREAD/XACK against the same stream without
+// caring whether the deployment is a single standalone node, a pooled set
+// of connections to one node, or a full Redis Cluster. `RedisBackend`
+// picks the right connection machinery once at startup (from env) and
+// exposes the handful of stream commands Q-Bridge actually uses through
+// the `StreamStore` trait, so `process_request` and the consumer loop
+// don't need to know which mode they're running in.
+//
+// NOTE: in `Cluster` mode the stream key MUST hash to a single slot (a
+// single Redis Cluster stream cannot span slots), so `STREAM_NAME` below,
+// which the gateway/buffer crates import, is wrapped in a `{q_bridge}`
+// hashtag. Don't widen
+// that hashtag unless every key that needs to land on the same slot is
+// updated to match.
+
+use async_trait::async_trait;
+use bb8_redis::{bb8, RedisConnectionManager};
+use redis::cluster::ClusterClient;
+use redis::streams::{StreamAutoClaimReply, StreamRangeReply, StreamReadOptions};
+use redis::{AsyncCommands, Client, RedisResult, Value};
+
+const DEFAULT_POOL_MAX_SIZE: u32 = 16;
+const DEFAULT_STREAM_MAXLEN: usize = 100_000;
+const DEFAULT_ADMISSION_HIGH_WATER_MARK: i64 = 80_000;
+
+/// Naming convention for the per-request Pub/Sub reply channel used by
+/// the synchronous submit path: the gateway subscribes to this channel
+/// before buffering a request, and the buffer consumer publishes a
+/// `SubmitRequestResult` to it once that request has been processed.
+pub fn reply_channel(request_id: &str) -> String {
+    format!("q_bridge:reply:{request_id}")
+}
+
+/// The stream every Q-Bridge ingress path (gRPC/REST gateway and the
+/// Arrow Flight transport) buffers requests onto, and the one the buffer
+/// consumer reads from. Carries a `{q_bridge}` hashtag so that, in Redis
+/// Cluster mode, every key Q-Bridge touches for it hashes to the same
+/// slot (a single Redis Cluster stream cannot span slots) — don't widen
+/// that hashtag unless every key that needs to land on the same slot is
+/// updated to match.
+pub const STREAM_NAME: &str = "{q_bridge}:q_bridge_stream";
+
+/// Approximate cap passed to `XADD ... MAXLEN ~`, so a sustained burst
+/// trims the stream instead of growing it unbounded.
+pub fn stream_maxlen() -> usize {
+    std::env::var("STREAM_MAXLEN")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_STREAM_MAXLEN)
+}
+
+/// Admission high-water mark: once `XLEN` reaches this, `buffer_request`
+/// rejects new entries instead of buffering them. Kept comfortably below
+/// `stream_maxlen()` so admission control kicks in before approximate
+/// trimming would start dropping the oldest entries.
+pub fn admission_high_water_mark() -> i64 {
+    std::env::var("STREAM_HIGH_WATER_MARK")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_ADMISSION_HIGH_WATER_MARK)
+}
+
+/// Why `buffer_request` declined to buffer an entry. Kept backend-agnostic
+/// (not a `tonic::Status`) since every ingress path maps it onto its own
+/// transport's error type (gRPC `Status`, an HTTP response, ...).
+#[derive(Debug)]
+pub enum BufferRequestError {
+    /// The stream has backed up past the configured high-water mark.
+    Saturated { len: i64 },
+    Redis(redis::RedisError),
+}
+
+impl std::fmt::Display for BufferRequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BufferRequestError::Saturated { len } => {
+                write!(f, "buffer saturated ({len} entries pending)")
+            }
+            BufferRequestError::Redis(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for BufferRequestError {}
+
+/// Checks the admission high-water mark and, if there's room, `XADD`s
+/// `fields` onto `stream` capped to `maxlen`. Shared by every ingress path
+/// (gRPC/REST gateway, Arrow Flight `do_put`) so a client can't bypass the
+/// gateway's backpressure by writing onto the stream through a different
+/// front door.
+pub async fn buffer_request(
+    redis: &RedisBackend,
+    stream: &str,
+    fields: &[(&str, &[u8])],
+    high_water_mark: i64,
+    maxlen: usize,
+) -> Result<(), BufferRequestError> {
+    let len = redis
+        .xlen(stream)
+        .await
+        .map_err(BufferRequestError::Redis)?;
+    if len >= high_water_mark {
+        return Err(BufferRequestError::Saturated { len });
+    }
+
+    redis
+        .xadd_capped(stream, "*", fields, maxlen)
+        .await
+        .map_err(BufferRequestError::Redis)?;
+
+    Ok(())
+}
+
+/// Selects how `RedisBackend` talks to Redis. Controlled by the
+/// `REDIS_BACKEND` env var (`single` | `pool` | `cluster`), defaulting to
+/// `single` to match the previous hard-coded behavior.
+///
+/// `Pool` and `Cluster` also carry a plain `redis::Client` against the
+/// first configured node (`admin`). Pub/Sub needs a dedicated connection
+/// that isn't shared through a multiplexed pool, and Redis Cluster's
+/// pub/sub messages propagate cluster-wide regardless of which node you
+/// connect to, so a single seed-node client covers both cases.
+#[derive(Clone)]
+pub enum RedisBackend {
+    /// One `redis::Client`, a fresh multiplexed connection per call.
+    /// This is the original behavior, kept as the default so existing
+    /// single-node deployments don't need any env changes.
+    Single(Client),
+    /// A bb8 pool of multiplexed connections against a single node.
+    Pool {
+        pool: bb8::Pool<RedisConnectionManager>,
+        admin: Client,
+    },
+    /// A Redis Cluster client, used when Redis runs as a cluster sharded
+    /// across multiple nodes (the stream itself still lives on one slot).
+    Cluster {
+        client: ClusterClient,
+        admin: Client,
+    },
+}
+
+impl RedisBackend {
+    /// Builds a backend from `REDIS_BACKEND` / `REDIS_URLS` / `REDIS_POOL_MAX_SIZE`.
+    ///
+    /// `REDIS_URLS` is a comma-separated list of node URLs. `single` and
+    /// `pool` only use the first entry; `cluster` uses all of them as seed
+    /// nodes. Falls back to `redis://127.0.0.1:6379/` when unset, so the
+    /// previous hard-coded single-node setup keeps working untouched.
+    pub async fn from_env() -> Result<Self, Box<dyn std::error::Error>> {
+        let urls: Vec<String> = std::env::var("REDIS_URLS")
+            .unwrap_or_else(|_| "redis://127.0.0.1:6379/".to_string())
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+        let mode = std::env::var("REDIS_BACKEND").unwrap_or_else(|_| "single".to_string());
+
+        match mode.as_str() {
+            "pool" => {
+                let url = urls.first().ok_or("REDIS_URLS must not be empty")?;
+                let manager = RedisConnectionManager::new(url.as_str())?;
+                let max_size = std::env::var("REDIS_POOL_MAX_SIZE")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(DEFAULT_POOL_MAX_SIZE);
+                let pool = bb8::Pool::builder()
+                    .max_size(max_size)
+                    .build(manager)
+                    .await?;
+                let admin = Client::open(url.as_str())?;
+                Ok(RedisBackend::Pool { pool, admin })
+            }
+            "cluster" => {
+                let admin_url = urls.first().ok_or("REDIS_URLS must not be empty")?.clone();
+                let client = ClusterClient::new(urls)?;
+                let admin = Client::open(admin_url.as_str())?;
+                Ok(RedisBackend::Cluster { client, admin })
+            }
+            "single" => {
+                let url = urls.first().ok_or("REDIS_URLS must not be empty")?;
+                Ok(RedisBackend::Single(Client::open(url.as_str())?))
+            }
+            other => Err(format!("unknown REDIS_BACKEND '{other}', expected single|pool|cluster").into()),
+        }
+    }
+
+    /// Opens a dedicated (non-pooled) Pub/Sub connection, used to wait on
+    /// a per-request reply channel in synchronous submit mode.
+    pub async fn pubsub_connection(&self) -> RedisResult<redis::aio::PubSub> {
+        let client = match self {
+            RedisBackend::Single(client) => client,
+            RedisBackend::Pool { admin, .. } => admin,
+            RedisBackend::Cluster { admin, .. } => admin,
+        };
+        Ok(client.get_async_connection().await?.into_pubsub())
+    }
+}
+
+/// The subset of Redis stream commands `process_request` and the buffer
+/// consumer need, implemented once per `RedisBackend` variant so callers
+/// don't have to match on the backend themselves.
+#[async_trait]
+pub trait StreamStore: Send + Sync {
+    async fn xadd(&self, stream: &str, id: &str, fields: &[(&str, &[u8])]) -> RedisResult<String>;
+
+    /// Like `xadd`, but trims the stream to approximately `maxlen` entries
+    /// in the same call (`XADD ... MAXLEN ~ <maxlen>`), bounding memory
+    /// use under sustained load.
+    async fn xadd_capped(
+        &self,
+        stream: &str,
+        id: &str,
+        fields: &[(&str, &[u8])],
+        maxlen: usize,
+    ) -> RedisResult<String>;
+
+    /// Current length of the stream, used as an admission-control signal.
+    async fn xlen(&self, stream: &str) -> RedisResult<i64>;
+
+    async fn xread_options(
+        &self,
+        stream: &str,
+        id: &str,
+        opts: &StreamReadOptions,
+    ) -> RedisResult<Value>;
+
+    async fn xack(&self, stream: &str, group: &str, ids: &[&str]) -> RedisResult<()>;
+
+    /// Claims pending entries idle longer than `min_idle_ms`, starting the
+    /// scan at `cursor` (`"0-0"` for the first page). Used by the buffer
+    /// consumer's stalled-message reclaim loop.
+    async fn xautoclaim(
+        &self,
+        stream: &str,
+        group: &str,
+        consumer: &str,
+        min_idle_ms: i64,
+        cursor: &str,
+    ) -> RedisResult<StreamAutoClaimReply>;
+
+    /// Increments a field in a Redis hash and returns the new value. Used
+    /// to track per-message delivery counts outside of the stream itself.
+    async fn hincr(&self, key: &str, field: &str, delta: i64) -> RedisResult<i64>;
+
+    /// Reads entries in `[start, end]` (Redis range syntax, e.g. `"-"`/`"+"`
+    /// for the full stream). Used by the Flight transport to serve buffered
+    /// requests as Arrow record batches.
+    async fn xrange(&self, stream: &str, start: &str, end: &str) -> RedisResult<StreamRangeReply>;
+
+    /// Publishes a message on a Pub/Sub channel. Used by the buffer
+    /// consumer to deliver results to a gateway awaiting on
+    /// `q_bridge:reply:<request_id>`.
+    async fn publish(&self, channel: &str, payload: &[u8]) -> RedisResult<()>;
+}
+
+#[async_trait]
+impl StreamStore for RedisBackend {
+    async fn xadd(&self, stream: &str, id: &str, fields: &[(&str, &[u8])]) -> RedisResult<String> {
+        match self {
+            RedisBackend::Single(client) => {
+                let mut conn = client.get_multiplexed_async_connection().await?;
+                conn.xadd(stream, id, fields).await
+            }
+            RedisBackend::Pool { pool, .. } => {
+                let mut conn = pool.get().await.map_err(pool_error)?;
+                conn.xadd(stream, id, fields).await
+            }
+            RedisBackend::Cluster { client, .. } => {
+                let mut conn = client.get_async_connection().await?;
+                conn.xadd(stream, id, fields).await
+            }
+        }
+    }
+
+    async fn xadd_capped(
+        &self,
+        stream: &str,
+        id: &str,
+        fields: &[(&str, &[u8])],
+        maxlen: usize,
+    ) -> RedisResult<String> {
+        let maxlen = redis::streams::StreamMaxlen::Approx(maxlen);
+        match self {
+            RedisBackend::Single(client) => {
+                let mut conn = client.get_multiplexed_async_connection().await?;
+                conn.xadd_maxlen(stream, maxlen, id, fields).await
+            }
+            RedisBackend::Pool { pool, .. } => {
+                let mut conn = pool.get().await.map_err(pool_error)?;
+                conn.xadd_maxlen(stream, maxlen, id, fields).await
+            }
+            RedisBackend::Cluster { client, .. } => {
+                let mut conn = client.get_async_connection().await?;
+                conn.xadd_maxlen(stream, maxlen, id, fields).await
+            }
+        }
+    }
+
+    async fn xlen(&self, stream: &str) -> RedisResult<i64> {
+        match self {
+            RedisBackend::Single(client) => {
+                let mut conn = client.get_multiplexed_async_connection().await?;
+                conn.xlen(stream).await
+            }
+            RedisBackend::Pool { pool, .. } => {
+                let mut conn = pool.get().await.map_err(pool_error)?;
+                conn.xlen(stream).await
+            }
+            RedisBackend::Cluster { client, .. } => {
+                let mut conn = client.get_async_connection().await?;
+                conn.xlen(stream).await
+            }
+        }
+    }
+
+    async fn xread_options(
+        &self,
+        stream: &str,
+        id: &str,
+        opts: &StreamReadOptions,
+    ) -> RedisResult<Value> {
+        match self {
+            RedisBackend::Single(client) => {
+                let mut conn = client.get_multiplexed_async_connection().await?;
+                conn.xread_options(&[stream], &[id], opts).await
+            }
+            RedisBackend::Pool { pool, .. } => {
+                let mut conn = pool.get().await.map_err(pool_error)?;
+                conn.xread_options(&[stream], &[id], opts).await
+            }
+            RedisBackend::Cluster { client, .. } => {
+                let mut conn = client.get_async_connection().await?;
+                conn.xread_options(&[stream], &[id], opts).await
+            }
+        }
+    }
+
+    async fn xack(&self, stream: &str, group: &str, ids: &[&str]) -> RedisResult<()> {
+        match self {
+            RedisBackend::Single(client) => {
+                let mut conn = client.get_multiplexed_async_connection().await?;
+                conn.xack(stream, group, ids).await
+            }
+            RedisBackend::Pool { pool, .. } => {
+                let mut conn = pool.get().await.map_err(pool_error)?;
+                conn.xack(stream, group, ids).await
+            }
+            RedisBackend::Cluster { client, .. } => {
+                let mut conn = client.get_async_connection().await?;
+                conn.xack(stream, group, ids).await
+            }
+        }
+    }
+
+    async fn xautoclaim(
+        &self,
+        stream: &str,
+        group: &str,
+        consumer: &str,
+        min_idle_ms: i64,
+        cursor: &str,
+    ) -> RedisResult<StreamAutoClaimReply> {
+        match self {
+            RedisBackend::Single(client) => {
+                let mut conn = client.get_multiplexed_async_connection().await?;
+                conn.xautoclaim(stream, group, consumer, min_idle_ms, cursor).await
+            }
+            RedisBackend::Pool { pool, .. } => {
+                let mut conn = pool.get().await.map_err(pool_error)?;
+                conn.xautoclaim(stream, group, consumer, min_idle_ms, cursor).await
+            }
+            RedisBackend::Cluster { client, .. } => {
+                let mut conn = client.get_async_connection().await?;
+                conn.xautoclaim(stream, group, consumer, min_idle_ms, cursor).await
+            }
+        }
+    }
+
+    async fn hincr(&self, key: &str, field: &str, delta: i64) -> RedisResult<i64> {
+        match self {
+            RedisBackend::Single(client) => {
+                let mut conn = client.get_multiplexed_async_connection().await?;
+                conn.hincr(key, field, delta).await
+            }
+            RedisBackend::Pool { pool, .. } => {
+                let mut conn = pool.get().await.map_err(pool_error)?;
+                conn.hincr(key, field, delta).await
+            }
+            RedisBackend::Cluster { client, .. } => {
+                let mut conn = client.get_async_connection().await?;
+                conn.hincr(key, field, delta).await
+            }
+        }
+    }
+
+    async fn xrange(&self, stream: &str, start: &str, end: &str) -> RedisResult<StreamRangeReply> {
+        match self {
+            RedisBackend::Single(client) => {
+                let mut conn = client.get_multiplexed_async_connection().await?;
+                conn.xrange(stream, start, end).await
+            }
+            RedisBackend::Pool { pool, .. } => {
+                let mut conn = pool.get().await.map_err(pool_error)?;
+                conn.xrange(stream, start, end).await
+            }
+            RedisBackend::Cluster { client, .. } => {
+                let mut conn = client.get_async_connection().await?;
+                conn.xrange(stream, start, end).await
+            }
+        }
+    }
+
+    async fn publish(&self, channel: &str, payload: &[u8]) -> RedisResult<()> {
+        match self {
+            RedisBackend::Single(client) => {
+                let mut conn = client.get_multiplexed_async_connection().await?;
+                conn.publish(channel, payload).await
+            }
+            RedisBackend::Pool { pool, .. } => {
+                let mut conn = pool.get().await.map_err(pool_error)?;
+                conn.publish(channel, payload).await
+            }
+            RedisBackend::Cluster { client, .. } => {
+                let mut conn = client.get_async_connection().await?;
+                conn.publish(channel, payload).await
+            }
+        }
+    }
+}
+
+/// Maps a bb8 pool-acquisition failure onto `redis::RedisError` so pool
+/// callers can bubble it up through the same `RedisResult` as the other
+/// backends.
+fn pool_error(e: bb8::RunError<redis::RedisError>) -> redis::RedisError {
+    redis::RedisError::from((
+        redis::ErrorKind::IoError,
+        "failed to acquire pooled Redis connection",
+        e.to_string(),
+    ))
+}
@@ -1,59 +1,99 @@
-use actix_web::{web, App, HttpResponse, HttpServer, Responder};
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use futures::StreamExt;
 use prost::Message;
-use redis::AsyncCommands;
+use std::time::Duration;
 use tonic::{transport::Server, Request, Response, Status};
 use tracing::{error, info, instrument};
 use tracing_subscriber::prelude::*;
 use uuid::Uuid;
 
+mod middleware;
+use middleware::{CorrelationId, CorrelationIdLayer, RequestId};
+
 // Import the shared protobuf definitions from the `common` crate
 use common::gateway::{
     gateway_service_server::{GatewayService, GatewayServiceServer},
-    InternalRequest, SubmitRequestResponse,
+    InternalRequest, SubmitRequestResponse, SubmitRequestResult,
+};
+use common::redis_backend::{
+    admission_high_water_mark, buffer_request as buffer_request_capped, reply_channel,
+    stream_maxlen, BufferRequestError, RedisBackend, STREAM_NAME,
 };
 
-const REDIS_URL: &str = "redis://127.0.0.1:6379/";
-const STREAM_NAME: &str = "q_bridge_stream";
+// How long `SubmitRequestAndWait` / `wait=true` block for a reply before
+// falling back to the fire-and-forget "accepted" result.
+const DEFAULT_WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Retry-After hint sent alongside a 429 when the buffer is saturated.
+const RETRY_AFTER_SECS: u64 = 1;
 
 // Shared application state, designed to be cloned
 #[derive(Clone)]
 struct AppState {
-    redis_client: redis::Client,
+    redis: std::sync::Arc<RedisBackend>,
 }
 
-// Helper function containing the core logic for processing a request
-#[instrument(skip(redis_client, internal_req))]
-async fn process_request(
-    mut internal_req: InternalRequest,
-    redis_client: &redis::Client,
-) -> Result<SubmitRequestResponse, Status> {
-    if internal_req.request_id.is_empty() {
-        internal_req.request_id = Uuid::new_v4().to_string();
-    }
-    let request_id = internal_req.request_id.clone();
-    info!(request_id = %request_id, "Processing request");
-
-    let mut conn = redis_client
-        .get_multiplexed_async_connection()
-        .await
-        .map_err(|e| {
-            error!("Failed to get Redis connection: {}", e);
-            Status::internal("Failed to connect to buffer")
-        })?;
-
+// Encodes and buffers a request, shared by the fire-and-forget and
+// wait-for-result paths below. Rejects with `resource_exhausted` when the
+// stream has backed up past `admission_high_water_mark()`, and trims the
+// stream to `stream_maxlen()` on every successful add so a sustained
+// burst can't grow it without bound. The actual check-then-XADD lives in
+// `common::redis_backend::buffer_request` so the Flight transport's
+// `do_put` goes through the exact same admission control instead of
+// bypassing it.
+async fn buffer_request(internal_req: &InternalRequest, redis: &RedisBackend) -> Result<(), Status> {
     let mut buf = Vec::new();
     internal_req.encode(&mut buf).map_err(|e| {
         error!("Failed to encode protobuf message: {}", e);
         Status::internal("Failed to serialize request")
     })?;
 
-    let _: () = conn
-        .xadd(STREAM_NAME, "*", &[("payload", &buf)])
-        .await
-        .map_err(|e| {
+    buffer_request_capped(
+        redis,
+        STREAM_NAME,
+        &[("payload", buf.as_slice())],
+        admission_high_water_mark(),
+        stream_maxlen(),
+    )
+    .await
+    .map_err(|e| match e {
+        BufferRequestError::Saturated { len } => Status::resource_exhausted(format!(
+            "buffer saturated ({len} entries pending), retry after {RETRY_AFTER_SECS}s"
+        )),
+        BufferRequestError::Redis(e) => {
             error!("Failed to add message to Redis stream: {}", e);
             Status::internal("Failed to write to buffer")
-        })?;
+        }
+    })
+}
+
+// Stamps `internal_req` with the correlation id the `CorrelationId`
+// middleware resolved for this call (reusing the caller's `x-request-id`/
+// `x-correlation-id` if it sent one, else the UUID the middleware minted).
+// The id is recorded under a dedicated metadata key rather than
+// overwriting `request_id` outright, but also backfills `request_id` when
+// the caller left it empty so the two agree in the common case.
+fn stamp_correlation_id(internal_req: &mut InternalRequest, correlation_id: &str) {
+    internal_req
+        .metadata
+        .insert("x-request-id".to_string(), correlation_id.to_string());
+    if internal_req.request_id.is_empty() {
+        internal_req.request_id = correlation_id.to_string();
+    }
+}
+
+// Helper function containing the core logic for processing a request
+#[instrument(skip(redis, internal_req))]
+async fn process_request(
+    mut internal_req: InternalRequest,
+    redis: &RedisBackend,
+    correlation_id: &str,
+) -> Result<SubmitRequestResponse, Status> {
+    stamp_correlation_id(&mut internal_req, correlation_id);
+    let request_id = internal_req.request_id.clone();
+    info!(request_id = %request_id, "Processing request");
+
+    buffer_request(&internal_req, redis).await?;
 
     info!(request_id = %request_id, "Request accepted and buffered");
 
@@ -63,6 +103,63 @@ async fn process_request(
     })
 }
 
+// Buffers a request, then blocks on its reply channel (subscribed before
+// buffering, so there's no race with a consumer that processes it fast)
+// until the buffer consumer publishes a result or `timeout` elapses. On
+// timeout this falls back to the same "accepted" result `process_request`
+// would have returned, so a slow consumer never fails the call outright.
+#[instrument(skip(redis, internal_req))]
+async fn process_request_and_wait(
+    mut internal_req: InternalRequest,
+    redis: &RedisBackend,
+    timeout: Duration,
+    correlation_id: &str,
+) -> Result<SubmitRequestResult, Status> {
+    stamp_correlation_id(&mut internal_req, correlation_id);
+    let request_id = internal_req.request_id.clone();
+    let channel = reply_channel(&request_id);
+    info!(request_id = %request_id, "Processing request with wait");
+
+    let mut pubsub = redis.pubsub_connection().await.map_err(|e| {
+        error!("Failed to open Pub/Sub connection: {}", e);
+        Status::internal("Failed to subscribe for reply")
+    })?;
+    pubsub.subscribe(&channel).await.map_err(|e| {
+        error!("Failed to subscribe to reply channel: {}", e);
+        Status::internal("Failed to subscribe for reply")
+    })?;
+    let mut replies = pubsub.into_on_message();
+
+    buffer_request(&internal_req, redis).await?;
+    info!(request_id = %request_id, "Request buffered, awaiting result");
+
+    match tokio::time::timeout(timeout, replies.next()).await {
+        Ok(Some(msg)) => {
+            let payload: Vec<u8> = msg
+                .get_payload()
+                .map_err(|e| Status::internal(format!("malformed reply payload: {e}")))?;
+            SubmitRequestResult::decode(payload.as_slice())
+                .map_err(|e| Status::internal(format!("failed to decode reply: {e}")))
+        }
+        Ok(None) => {
+            info!(request_id = %request_id, "Reply subscription closed before a result arrived");
+            Ok(SubmitRequestResult {
+                request_id,
+                status: "accepted".to_string(),
+                result: Vec::new(),
+            })
+        }
+        Err(_) => {
+            info!(request_id = %request_id, "Timed out waiting for result; falling back to fire-and-forget");
+            Ok(SubmitRequestResult {
+                request_id,
+                status: "accepted".to_string(),
+                result: Vec::new(),
+            })
+        }
+    }
+}
+
 // The gRPC service implementation
 struct MyGatewayService {
     state: AppState,
@@ -75,20 +172,67 @@ impl GatewayService for MyGatewayService {
         &self,
         request: Request<InternalRequest>,
     ) -> Result<Response<SubmitRequestResponse>, Status> {
+        // `CorrelationIdLayer` stashes the resolved id as a request
+        // extension before tonic ever decodes the body.
+        let correlation_id = request
+            .extensions()
+            .get::<RequestId>()
+            .map(|id| id.0.clone())
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+        let req = request.into_inner();
+        let result = process_request(req, &self.state.redis, &correlation_id).await?;
+        Ok(Response::new(result))
+    }
+
+    #[instrument(skip(self, request))]
+    async fn submit_request_and_wait(
+        &self,
+        request: Request<InternalRequest>,
+    ) -> Result<Response<SubmitRequestResult>, Status> {
+        let correlation_id = request
+            .extensions()
+            .get::<RequestId>()
+            .map(|id| id.0.clone())
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
         let req = request.into_inner();
-        let result = process_request(req, &self.state.redis_client).await?;
+        let result = process_request_and_wait(
+            req,
+            &self.state.redis,
+            DEFAULT_WAIT_TIMEOUT,
+            &correlation_id,
+        )
+        .await?;
         Ok(Response::new(result))
     }
 }
 
+// Query params accepted by `/submit`.
+#[derive(serde::Deserialize)]
+struct SubmitQuery {
+    #[serde(default)]
+    wait: bool,
+}
+
 // The Actix-Web REST endpoint handler
-#[instrument(skip(state, payload))]
+#[instrument(skip(state, req, payload))]
 async fn rest_submit_request(
     state: web::Data<AppState>,
+    req: HttpRequest,
+    query: web::Query<SubmitQuery>,
     payload: web::Json<serde_json::Value>,
 ) -> impl Responder {
+    // `CorrelationId` stashes the resolved id as a request extension;
+    // `process_request`/`process_request_and_wait` stamp it onto the
+    // buffered `InternalRequest` so it's the single id used end to end,
+    // rather than this handler minting its own on top of it.
+    let correlation_id = req
+        .extensions()
+        .get::<RequestId>()
+        .map(|id| id.0.clone())
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
     let internal_req = InternalRequest {
-        request_id: Uuid::new_v4().to_string(),
+        request_id: String::new(),
         agent_id: payload
             .get("agent_id")
             .and_then(|v| v.as_str())
@@ -103,15 +247,44 @@ async fn rest_submit_request(
         metadata: Default::default(),
     };
 
-    match process_request(internal_req, &state.redis_client).await {
+    if query.wait {
+        return match process_request_and_wait(
+            internal_req,
+            &state.redis,
+            DEFAULT_WAIT_TIMEOUT,
+            &correlation_id,
+        )
+        .await
+        {
+            Ok(result) => HttpResponse::Ok().json(result),
+            Err(status) => {
+                error!("Failed to process REST request with wait: {}", status);
+                status_to_response(status)
+            }
+        };
+    }
+
+    match process_request(internal_req, &state.redis, &correlation_id).await {
         Ok(response) => HttpResponse::Accepted().json(response),
         Err(status) => {
             error!("Failed to process REST request: {}", status);
-            HttpResponse::InternalServerError().body(status.message().to_string())
+            status_to_response(status)
         }
     }
 }
 
+// Maps a gRPC `Status` onto the equivalent REST response, in particular
+// turning admission-control rejections into a 429 with `Retry-After`
+// instead of a generic 500.
+fn status_to_response(status: Status) -> HttpResponse {
+    if status.code() == tonic::Code::ResourceExhausted {
+        return HttpResponse::TooManyRequests()
+            .insert_header(("Retry-After", RETRY_AFTER_SECS.to_string()))
+            .body(status.message().to_string());
+    }
+    HttpResponse::InternalServerError().body(status.message().to_string())
+}
+
 // A simple health check endpoint for Actix-Web
 async fn health_check() -> impl Responder {
     HttpResponse::Ok().body("OK")
@@ -125,9 +298,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .with(tracing_subscriber::EnvFilter::from_default_env())
         .init();
 
-    // Initialize shared state
+    // Initialize shared state. `RedisBackend::from_env` picks standalone /
+    // pool / cluster based on `REDIS_BACKEND` and `REDIS_URLS`.
     let app_state = AppState {
-        redis_client: redis::Client::open(REDIS_URL)?,
+        redis: std::sync::Arc::new(RedisBackend::from_env().await?),
     };
     // Wrap state for Actix-Web
     let web_data = web::Data::new(app_state.clone());
@@ -135,7 +309,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // --- gRPC Server Task ---
     let grpc_addr = "127.0.0.1:50051".parse()?;
     let grpc_service = MyGatewayService { state: app_state };
-    let grpc_server = Server::builder().add_service(GatewayServiceServer::new(grpc_service));
+    let grpc_server = Server::builder()
+        .layer(tower::ServiceBuilder::new().layer(CorrelationIdLayer).into_inner())
+        .add_service(GatewayServiceServer::new(grpc_service));
     info!("gRPC server listening on {}", grpc_addr);
     let grpc_handle = tokio::spawn(grpc_server.serve(grpc_addr));
 
@@ -144,6 +320,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     info!("REST server listening on {}", rest_addr);
     let rest_server = HttpServer::new(move || {
         App::new()
+            .wrap(CorrelationId)
             .app_data(web_data.clone())
             .route("/submit", web::post().to(rest_submit_request))
             .route("/health", web::get().to(health_check))
@@ -166,18 +343,50 @@ mod tests {
     use super::*;
     use redis::Commands;
 
+    const TEST_REDIS_URL: &str = "redis://127.0.0.1:6379/";
+
     // Helper to get a sync redis connection for test verification
     fn get_sync_redis_connection() -> redis::Connection {
-        redis::Client::open(REDIS_URL)
+        redis::Client::open(TEST_REDIS_URL)
             .expect("Failed to create Redis client for test")
             .get_connection()
             .expect("Failed to get sync Redis connection for test")
     }
 
+    #[test]
+    fn stamp_correlation_id_backfills_empty_request_id() {
+        let mut req = InternalRequest {
+            request_id: String::new(),
+            agent_id: "agent-1".to_string(),
+            payload: vec![],
+            metadata: Default::default(),
+        };
+
+        stamp_correlation_id(&mut req, "corr-1");
+
+        assert_eq!(req.request_id, "corr-1");
+        assert_eq!(req.metadata.get("x-request-id"), Some(&"corr-1".to_string()));
+    }
+
+    #[test]
+    fn stamp_correlation_id_preserves_existing_request_id() {
+        let mut req = InternalRequest {
+            request_id: "caller-assigned".to_string(),
+            agent_id: "agent-1".to_string(),
+            payload: vec![],
+            metadata: Default::default(),
+        };
+
+        stamp_correlation_id(&mut req, "corr-1");
+
+        assert_eq!(req.request_id, "caller-assigned");
+        assert_eq!(req.metadata.get("x-request-id"), Some(&"corr-1".to_string()));
+    }
+
     #[tokio::test]
     #[ignore] // This test requires a running Redis instance and should be run with `cargo test -- --ignored`
     async fn test_process_request_success() {
-        let redis_client = redis::Client::open(REDIS_URL).unwrap();
+        let redis = RedisBackend::Single(redis::Client::open(TEST_REDIS_URL).unwrap());
         let mut conn = get_sync_redis_connection();
 
         // Clean up before test
@@ -191,7 +400,7 @@ mod tests {
         };
 
         // Call the function under test
-        let result = process_request(request, &redis_client).await;
+        let result = process_request(request, &redis, "test-req-123").await;
         assert!(result.is_ok());
 
         let response = result.unwrap();
@@ -0,0 +1,359 @@
+// Cross-cutting correlation-ID + access-log middleware, shared by the
+// gRPC and REST front doors so a single id traces a request end to end:
+// gateway -> buffer stream -> consumer. Both sides do the same three
+// things: read `x-request-id`/`x-correlation-id` if the caller sent one
+// (else mint a UUID), stash it somewhere the handler can read it back,
+// and log one structured access-log line with the elapsed time once the
+// response is ready.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use http_body::Body as HttpBody;
+use pin_project_lite::pin_project;
+use tracing::{info, Instrument};
+use uuid::Uuid;
+
+/// The resolved correlation id for one request, stashed as an extension
+/// on both the `http::Request` (gRPC) and `actix_web::HttpRequest` (REST)
+/// so handlers can read it back without re-parsing headers.
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+fn extract_or_mint_request_id(headers: &http::HeaderMap) -> String {
+    headers
+        .get("x-request-id")
+        .or_else(|| headers.get("x-correlation-id"))
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| Uuid::new_v4().to_string())
+}
+
+// --- gRPC side: a tower::Layer/Service wrapping the tonic router -------
+
+#[derive(Clone, Default)]
+pub struct CorrelationIdLayer;
+
+impl<S> tower::Layer<S> for CorrelationIdLayer {
+    type Service = CorrelationIdService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CorrelationIdService { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct CorrelationIdService<S> {
+    inner: S,
+}
+
+/// Fields needed to emit the access-log line, captured at request-start
+/// and carried through to wherever the real RPC outcome becomes known.
+struct AccessLogCtx {
+    remote_addr: String,
+    rpc: String,
+    request_id: String,
+    start: Instant,
+}
+
+impl AccessLogCtx {
+    /// `grpc_status` is the raw `grpc-status` header/trailer value (a
+    /// `tonic::Code` as a decimal string, `"0"` for `Ok`), if one was
+    /// found — absent when the response body ended without a
+    /// `grpc-status` trailer. An error from the inner service itself is
+    /// logged by `log_transport_error` instead.
+    fn log(self, grpc_status: Option<&http::HeaderValue>) {
+        let elapsed_ms = self.start.elapsed().as_millis() as u64;
+        match grpc_status.and_then(|v| v.to_str().ok()) {
+            Some(status) => info!(
+                remote_addr = %self.remote_addr,
+                rpc = %self.rpc,
+                grpc_status = %status,
+                elapsed_ms,
+                request_id = %self.request_id,
+                "access log"
+            ),
+            None => info!(
+                remote_addr = %self.remote_addr,
+                rpc = %self.rpc,
+                elapsed_ms,
+                request_id = %self.request_id,
+                "access log (no grpc-status trailer)"
+            ),
+        }
+    }
+
+    fn log_transport_error(self) {
+        let elapsed_ms = self.start.elapsed().as_millis() as u64;
+        info!(
+            remote_addr = %self.remote_addr,
+            rpc = %self.rpc,
+            elapsed_ms,
+            request_id = %self.request_id,
+            "access log (transport error)"
+        );
+    }
+}
+
+pin_project! {
+    /// Wraps a gRPC response body so the access-log line can report the
+    /// real RPC outcome. Tonic always answers with HTTP 200 on the status
+    /// line and encodes the actual `grpc-status` (`Ok`, `resource_exhausted`,
+    /// `internal`, ...) as a trailer emitted once the body stream ends, so
+    /// logging `resp.status()` right after `call()` returns would log 200
+    /// for virtually every request, successes and rejections alike.
+    struct GrpcStatusLoggingBody<B> {
+        #[pin]
+        inner: B,
+        ctx: Option<AccessLogCtx>,
+    }
+}
+
+impl<B: HttpBody> HttpBody for GrpcStatusLoggingBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_data(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        self.project().inner.poll_data(cx)
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<http::HeaderMap>, Self::Error>> {
+        let this = self.project();
+        match std::task::ready!(this.inner.poll_trailers(cx)) {
+            Ok(trailers) => {
+                if let Some(ctx) = this.ctx.take() {
+                    ctx.log(trailers.as_ref().and_then(|t| t.get("grpc-status")));
+                }
+                Poll::Ready(Ok(trailers))
+            }
+            Err(e) => Poll::Ready(Err(e)),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl<S, ReqBody, ResBody> tower::Service<http::Request<ReqBody>> for CorrelationIdService<S>
+where
+    S: tower::Service<http::Request<ReqBody>, Response = http::Response<ResBody>>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+    ResBody: HttpBody + Send + 'static,
+{
+    type Response = http::Response<GrpcStatusLoggingBody<ResBody>>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: http::Request<ReqBody>) -> Self::Future {
+        let request_id = extract_or_mint_request_id(req.headers());
+        req.extensions_mut().insert(RequestId(request_id.clone()));
+
+        let rpc = req.uri().path().to_string();
+        let remote_addr = req
+            .extensions()
+            .get::<tonic::transport::server::TcpConnectInfo>()
+            .and_then(|info| info.remote_addr())
+            .map(|addr| addr.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        let start = Instant::now();
+
+        let span = tracing::info_span!("grpc_request", request_id = %request_id, rpc = %rpc);
+
+        // Standard tower "clone and swap" pattern: `poll_ready` was called
+        // on `self.inner`, so we hand the ready clone to the in-flight
+        // future and keep a fresh clone in `self` for the next call.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(
+            async move {
+                let ctx = AccessLogCtx {
+                    remote_addr,
+                    rpc,
+                    request_id,
+                    start,
+                };
+                match inner.call(req).await {
+                    Ok(resp) => {
+                        let (parts, body) = resp.into_parts();
+                        // A rejection raised before any data is streamed
+                        // (e.g. an interceptor, or an error returned
+                        // outright by the handler) is sent as a
+                        // "trailers-only" response with `grpc-status` in
+                        // the headers rather than a body trailer.
+                        if let Some(status) = parts.headers.get("grpc-status") {
+                            ctx.log(Some(status));
+                            Ok(http::Response::from_parts(
+                                parts,
+                                GrpcStatusLoggingBody { inner: body, ctx: None },
+                            ))
+                        } else {
+                            let body = GrpcStatusLoggingBody {
+                                inner: body,
+                                ctx: Some(ctx),
+                            };
+                            Ok(http::Response::from_parts(parts, body))
+                        }
+                    }
+                    Err(e) => {
+                        ctx.log_transport_error();
+                        Err(e)
+                    }
+                }
+            }
+            .instrument(span),
+        )
+    }
+}
+
+// --- REST side: an actix-web middleware --------------------------------
+
+pub struct CorrelationId;
+
+impl<S, B> actix_web::dev::Transform<S, actix_web::dev::ServiceRequest> for CorrelationId
+where
+    S: actix_web::dev::Service<
+            actix_web::dev::ServiceRequest,
+            Response = actix_web::dev::ServiceResponse<B>,
+            Error = actix_web::Error,
+        > + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = actix_web::dev::ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = CorrelationIdMiddleware<S>;
+    type InitError = ();
+    type Future = std::future::Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        std::future::ready(Ok(CorrelationIdMiddleware { service }))
+    }
+}
+
+pub struct CorrelationIdMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> actix_web::dev::Service<actix_web::dev::ServiceRequest> for CorrelationIdMiddleware<S>
+where
+    S: actix_web::dev::Service<
+            actix_web::dev::ServiceRequest,
+            Response = actix_web::dev::ServiceResponse<B>,
+            Error = actix_web::Error,
+        > + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = actix_web::dev::ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = futures::future::LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, req: actix_web::dev::ServiceRequest) -> Self::Future {
+        let request_id = extract_or_mint_request_id(req.headers());
+        req.extensions_mut().insert(RequestId(request_id.clone()));
+
+        let method = req.method().to_string();
+        let path = req.path().to_string();
+        let remote_addr = req
+            .connection_info()
+            .realip_remote_addr()
+            .unwrap_or("unknown")
+            .to_string();
+        let start = Instant::now();
+
+        let span =
+            tracing::info_span!("rest_request", request_id = %request_id, method = %method, path = %path);
+        let fut = self.service.call(req);
+
+        Box::pin(
+            async move {
+                let res = fut.await?;
+                let elapsed_ms = start.elapsed().as_millis() as u64;
+                info!(
+                    remote_addr = %remote_addr,
+                    method = %method,
+                    path = %path,
+                    status = res.status().as_u16(),
+                    elapsed_ms,
+                    request_id = %request_id,
+                    "access log"
+                );
+                Ok(res)
+            }
+            .instrument(span),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers_with(pairs: &[(&str, &str)]) -> http::HeaderMap {
+        let mut headers = http::HeaderMap::new();
+        for (k, v) in pairs {
+            headers.insert(
+                http::header::HeaderName::from_bytes(k.as_bytes()).unwrap(),
+                http::header::HeaderValue::from_str(v).unwrap(),
+            );
+        }
+        headers
+    }
+
+    #[test]
+    fn uses_x_request_id_when_present() {
+        let headers = headers_with(&[("x-request-id", "req-1")]);
+        assert_eq!(extract_or_mint_request_id(&headers), "req-1");
+    }
+
+    #[test]
+    fn falls_back_to_x_correlation_id() {
+        let headers = headers_with(&[("x-correlation-id", "corr-1")]);
+        assert_eq!(extract_or_mint_request_id(&headers), "corr-1");
+    }
+
+    #[test]
+    fn x_request_id_takes_precedence_over_x_correlation_id() {
+        let headers = headers_with(&[("x-request-id", "req-1"), ("x-correlation-id", "corr-1")]);
+        assert_eq!(extract_or_mint_request_id(&headers), "req-1");
+    }
+
+    #[test]
+    fn mints_a_uuid_when_no_header_is_present() {
+        let headers = headers_with(&[]);
+        let id = extract_or_mint_request_id(&headers);
+        assert!(Uuid::parse_str(&id).is_ok());
+    }
+
+    #[test]
+    fn mints_a_uuid_when_header_is_empty() {
+        let headers = headers_with(&[("x-request-id", "")]);
+        let id = extract_or_mint_request_id(&headers);
+        assert!(Uuid::parse_str(&id).is_ok());
+    }
+}
@@ -1,17 +1,114 @@
+use arrow_array::builder::{BinaryBuilder, ListBuilder, StringBuilder};
+use arrow_array::RecordBatch;
+use arrow_flight::encode::FlightDataEncoderBuilder;
 use arrow_flight::flight_service_server::{FlightService, FlightServiceServer};
 use arrow_flight::{
     Action, ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightInfo,
-    HandshakeRequest, HandshakeResponse, PollInfo, PutResult, SchemaResult, Ticket,
+    HandshakeRequest, HandshakeResponse, IpcMessage, PollInfo, PutResult, SchemaAsIpc,
+    SchemaResult, Ticket,
 };
+use arrow_ipc::writer::IpcWriteOptions;
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
 use async_trait::async_trait;
-use futures::stream::{self, Stream};
+use futures::stream::{self, Stream, TryStreamExt};
+use prost::Message;
 use std::pin::Pin;
+use std::sync::Arc;
 use tonic::{transport::Server, Request, Response, Status, Streaming};
+use tracing::{error, info, instrument};
+use tracing_subscriber::prelude::*;
+
+use common::gateway::InternalRequest;
+use common::redis_backend::{
+    admission_high_water_mark, buffer_request, stream_maxlen, BufferRequestError, RedisBackend,
+    StreamStore, STREAM_NAME,
+};
 
 type FlightStream<T> = Pin<Box<dyn Stream<Item = T> + Send + Sync + 'static>>;
 
-#[derive(Clone, Default)]
-pub struct MyFlightService {}
+const DEFAULT_BATCH_SIZE: usize = 1024;
+
+/// Arrow schema exposed by the Flight endpoint: one row per buffered
+/// `InternalRequest`, with the `metadata` map flattened into a pair of
+/// parallel key/value list columns (Arrow has no native map-as-columns
+/// convenience, and this keeps the schema simple for consumers).
+fn request_schema() -> SchemaRef {
+    let metadata_item = Arc::new(Field::new("item", DataType::Utf8, true));
+    Arc::new(Schema::new(vec![
+        Field::new("request_id", DataType::Utf8, false),
+        Field::new("agent_id", DataType::Utf8, false),
+        Field::new("payload", DataType::Binary, false),
+        Field::new(
+            "metadata_keys",
+            DataType::List(metadata_item.clone()),
+            false,
+        ),
+        Field::new("metadata_values", DataType::List(metadata_item), false),
+    ]))
+}
+
+/// The `Ticket` payload for `do_get`: which stream to read and the
+/// `XRANGE` bounds (`"-"`/`"+"` for the full stream).
+#[derive(serde::Serialize, serde::Deserialize)]
+struct FlightTicket {
+    stream: String,
+    #[serde(default = "default_start")]
+    start: String,
+    #[serde(default = "default_end")]
+    end: String,
+}
+
+fn default_start() -> String {
+    "-".to_string()
+}
+
+fn default_end() -> String {
+    "+".to_string()
+}
+
+fn batch_size() -> usize {
+    std::env::var("FLIGHT_BATCH_SIZE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_BATCH_SIZE)
+}
+
+/// Builds one `RecordBatch` out of up to `batch_size()` decoded requests.
+fn encode_batch(requests: &[InternalRequest]) -> Result<RecordBatch, arrow_schema::ArrowError> {
+    let mut request_id = StringBuilder::new();
+    let mut agent_id = StringBuilder::new();
+    let mut payload = BinaryBuilder::new();
+    let mut metadata_keys = ListBuilder::new(StringBuilder::new());
+    let mut metadata_values = ListBuilder::new(StringBuilder::new());
+
+    for req in requests {
+        request_id.append_value(&req.request_id);
+        agent_id.append_value(&req.agent_id);
+        payload.append_value(&req.payload);
+        for (k, v) in &req.metadata {
+            metadata_keys.values().append_value(k);
+            metadata_values.values().append_value(v);
+        }
+        metadata_keys.append(true);
+        metadata_values.append(true);
+    }
+
+    RecordBatch::try_new(
+        request_schema(),
+        vec![
+            Arc::new(request_id.finish()),
+            Arc::new(agent_id.finish()),
+            Arc::new(payload.finish()),
+            Arc::new(metadata_keys.finish()),
+            Arc::new(metadata_values.finish()),
+        ],
+    )
+}
+
+#[derive(Clone)]
+pub struct MyFlightService {
+    redis: Arc<RedisBackend>,
+}
 
 #[async_trait]
 impl FlightService for MyFlightService {
@@ -23,16 +120,119 @@ impl FlightService for MyFlightService {
     type ListActionsStream = FlightStream<Result<ActionType, Status>>;
     type ListFlightsStream = FlightStream<Result<FlightInfo, Status>>;
 
+    #[instrument(skip(self, request))]
     async fn do_get(
         &self,
-        _request: Request<Ticket>,
+        request: Request<Ticket>,
     ) -> Result<Response<Self::DoGetStream>, Status> {
-        println!("Received do_get request, returning empty stream as a placeholder.");
-        let stream = stream::empty();
-        Ok(Response::new(Box::pin(stream) as Self::DoGetStream))
+        let ticket = request.into_inner();
+        let ticket: FlightTicket = serde_json::from_slice(&ticket.ticket)
+            .map_err(|e| Status::invalid_argument(format!("invalid ticket: {e}")))?;
+
+        info!(stream = %ticket.stream, "Reading buffered requests for do_get");
+
+        let range = self
+            .redis
+            .xrange(&ticket.stream, &ticket.start, &ticket.end)
+            .await
+            .map_err(|e| {
+                error!("XRANGE failed: {}", e);
+                Status::internal("failed to read buffer")
+            })?;
+
+        let mut requests = Vec::with_capacity(range.ids.len());
+        for entry in &range.ids {
+            let Some(payload) = entry.map.get("payload") else {
+                continue;
+            };
+            let payload: Vec<u8> = redis::from_redis_value(payload)
+                .map_err(|e| Status::internal(format!("malformed payload field: {e}")))?;
+            match InternalRequest::decode(payload.as_slice()) {
+                Ok(req) => requests.push(req),
+                Err(e) => error!(message_id = %entry.id, "Failed to decode message: {}", e),
+            }
+        }
+
+        let chunk_size = batch_size();
+        let batches: Vec<RecordBatch> = requests
+            .chunks(chunk_size)
+            .map(encode_batch)
+            .collect::<Result<_, _>>()
+            .map_err(|e| Status::internal(format!("failed to build record batch: {e}")))?;
+
+        let batch_stream = stream::iter(batches.into_iter().map(Ok));
+        let flight_data_stream = FlightDataEncoderBuilder::new()
+            .with_schema(request_schema())
+            .build(batch_stream)
+            .map_err(Status::from);
+
+        Ok(Response::new(Box::pin(flight_data_stream) as Self::DoGetStream))
+    }
+
+    #[instrument(skip(self, request))]
+    async fn do_put(
+        &self,
+        request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoPutStream>, Status> {
+        let flight_data_stream = request.into_inner().map_err(|e| e.into());
+        let mut batches =
+            arrow_flight::decode::FlightRecordBatchStream::new_from_flight_data(flight_data_stream);
+
+        let mut rows_written = 0u64;
+        while let Some(batch) = batches
+            .try_next()
+            .await
+            .map_err(|e| Status::invalid_argument(format!("failed to decode Arrow batch: {e}")))?
+        {
+            rows_written += self.write_batch_to_stream(&batch).await?;
+        }
+
+        info!(rows_written, "do_put finished writing to Redis stream");
+
+        let result = stream::once(async move {
+            Ok(PutResult {
+                app_metadata: rows_written.to_string().into_bytes().into(),
+            })
+        });
+        Ok(Response::new(Box::pin(result) as Self::DoPutStream))
+    }
+
+    #[instrument(skip(self, _request))]
+    async fn get_schema(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<SchemaResult>, Status> {
+        let options = IpcWriteOptions::default();
+        let schema_result = SchemaAsIpc::new(&request_schema(), &options)
+            .try_into()
+            .map_err(|e: arrow_schema::ArrowError| Status::internal(e.to_string()))?;
+        Ok(Response::new(schema_result))
+    }
+
+    #[instrument(skip(self, request))]
+    async fn get_flight_info(
+        &self,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        let descriptor = request.into_inner();
+        let options = IpcWriteOptions::default();
+        let message: IpcMessage = SchemaAsIpc::new(&request_schema(), &options)
+            .try_into()
+            .map_err(|e: arrow_schema::ArrowError| Status::internal(e.to_string()))?;
+
+        let info = FlightInfo {
+            schema: message.0,
+            flight_descriptor: Some(descriptor),
+            endpoint: vec![],
+            total_records: -1,
+            total_bytes: -1,
+            ordered: false,
+            app_metadata: Default::default(),
+        };
+        Ok(Response::new(info))
     }
 
-    // --- Default implementations for other methods ---
+    // --- Default implementations for the remaining methods ---
     async fn handshake(
         &self,
         _request: Request<Streaming<HandshakeRequest>>,
@@ -45,30 +245,12 @@ impl FlightService for MyFlightService {
     ) -> Result<Response<Self::ListFlightsStream>, Status> {
         Err(Status::unimplemented("list_flights"))
     }
-    async fn get_flight_info(
-        &self,
-        _request: Request<FlightDescriptor>,
-    ) -> Result<Response<FlightInfo>, Status> {
-        Err(Status::unimplemented("get_flight_info"))
-    }
     async fn poll_flight_info(
         &self,
         _request: Request<FlightDescriptor>,
     ) -> Result<Response<PollInfo>, Status> {
         Err(Status::unimplemented("poll_flight_info"))
     }
-    async fn get_schema(
-        &self,
-        _request: Request<FlightDescriptor>,
-    ) -> Result<Response<SchemaResult>, Status> {
-        Err(Status::unimplemented("get_schema"))
-    }
-    async fn do_put(
-        &self,
-        _request: Request<Streaming<FlightData>>,
-    ) -> Result<Response<Self::DoPutStream>, Status> {
-        Err(Status::unimplemented("do_put"))
-    }
     async fn do_exchange(
         &self,
         _request: Request<Streaming<FlightData>>,
@@ -89,15 +271,173 @@ impl FlightService for MyFlightService {
     }
 }
 
+impl MyFlightService {
+    /// Decodes one Arrow `RecordBatch` back into `InternalRequest`s and
+    /// buffers each row onto `STREAM_NAME` through the same capped-`XADD` +
+    /// admission check as the gRPC/REST gateway, making `do_put` a real
+    /// bulk ingress path alongside it rather than a bypass around its
+    /// backpressure.
+    async fn write_batch_to_stream(&self, batch: &RecordBatch) -> Result<u64, Status> {
+        // `do_put` accepts arbitrary externally-supplied Arrow IPC batches,
+        // so the column count is checked and every column is downcast
+        // safely, both rejected with `invalid_argument` on a schema
+        // mismatch instead of panicking.
+        if batch.num_columns() < 3 {
+            return Err(Status::invalid_argument(format!(
+                "expected at least 3 columns (request_id, agent_id, payload), got {}",
+                batch.num_columns()
+            )));
+        }
+
+        let request_ids = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<arrow_array::StringArray>()
+            .ok_or_else(|| Status::invalid_argument("request_id column must be Utf8"))?;
+        let agent_ids = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<arrow_array::StringArray>()
+            .ok_or_else(|| Status::invalid_argument("agent_id column must be Utf8"))?;
+        let payloads = batch
+            .column(2)
+            .as_any()
+            .downcast_ref::<arrow_array::BinaryArray>()
+            .ok_or_else(|| Status::invalid_argument("payload column must be Binary"))?;
+
+        let high_water_mark = admission_high_water_mark();
+        let maxlen = stream_maxlen();
+
+        let mut written = 0u64;
+        for row in 0..batch.num_rows() {
+            let req = InternalRequest {
+                request_id: request_ids.value(row).to_string(),
+                agent_id: agent_ids.value(row).to_string(),
+                payload: payloads.value(row).to_vec(),
+                metadata: Default::default(),
+            };
+
+            let mut buf = Vec::new();
+            req.encode(&mut buf)
+                .map_err(|e| Status::internal(format!("failed to encode protobuf message: {e}")))?;
+
+            buffer_request(
+                &self.redis,
+                STREAM_NAME,
+                &[("payload", buf.as_slice())],
+                high_water_mark,
+                maxlen,
+            )
+            .await
+            .map_err(|e| match e {
+                BufferRequestError::Saturated { len } => {
+                    Status::resource_exhausted(format!("buffer saturated ({len} entries pending)"))
+                }
+                BufferRequestError::Redis(e) => {
+                    error!("Failed to add message to Redis stream: {}", e);
+                    Status::internal("failed to write to buffer")
+                }
+            })?;
+            written += 1;
+        }
+
+        Ok(written)
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_subscriber::EnvFilter::from_default_env())
+        .init();
+
     let addr = "127.0.0.1:50052".parse()?;
-    let service = MyFlightService::default();
+    let service = MyFlightService {
+        redis: Arc::new(RedisBackend::from_env().await?),
+    };
     let server = FlightServiceServer::new(service);
 
-    println!("Transport server listening on {}", addr);
+    info!("Transport server listening on {}", addr);
 
     Server::builder().add_service(server).serve(addr).await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_schema_has_expected_columns() {
+        let schema = request_schema();
+        let names: Vec<&str> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["request_id", "agent_id", "payload", "metadata_keys", "metadata_values"]
+        );
+        assert_eq!(schema.field(0).data_type(), &DataType::Utf8);
+        assert_eq!(schema.field(2).data_type(), &DataType::Binary);
+    }
+
+    #[test]
+    fn encode_batch_round_trips_rows_and_metadata() {
+        let mut metadata = std::collections::HashMap::new();
+        metadata.insert("x-request-id".to_string(), "abc-123".to_string());
+
+        let requests = vec![InternalRequest {
+            request_id: "abc-123".to_string(),
+            agent_id: "agent-1".to_string(),
+            payload: b"hello".to_vec(),
+            metadata,
+        }];
+
+        let batch = encode_batch(&requests).expect("batch should encode");
+        assert_eq!(batch.num_rows(), 1);
+
+        let request_ids = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<arrow_array::StringArray>()
+            .unwrap();
+        assert_eq!(request_ids.value(0), "abc-123");
+
+        let payloads = batch
+            .column(2)
+            .as_any()
+            .downcast_ref::<arrow_array::BinaryArray>()
+            .unwrap();
+        assert_eq!(payloads.value(0), b"hello");
+
+        let metadata_keys = batch
+            .column(3)
+            .as_any()
+            .downcast_ref::<arrow_array::ListArray>()
+            .unwrap()
+            .value(0);
+        let metadata_keys = metadata_keys
+            .as_any()
+            .downcast_ref::<arrow_array::StringArray>()
+            .unwrap();
+        assert_eq!(metadata_keys.value(0), "x-request-id");
+
+        let metadata_values = batch
+            .column(4)
+            .as_any()
+            .downcast_ref::<arrow_array::ListArray>()
+            .unwrap()
+            .value(0);
+        let metadata_values = metadata_values
+            .as_any()
+            .downcast_ref::<arrow_array::StringArray>()
+            .unwrap();
+        assert_eq!(metadata_values.value(0), "abc-123");
+    }
+
+    #[test]
+    fn encode_batch_handles_no_rows() {
+        let batch = encode_batch(&[]).expect("an empty batch should still encode");
+        assert_eq!(batch.num_rows(), 0);
+    }
+}